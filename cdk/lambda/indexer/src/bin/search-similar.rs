@@ -1,26 +1,101 @@
 //! `search-similar`
 //!
 //! You have to configure the following environment variables:
-//! - `DATABASE_BUCKET_NAME`: name of the S3 bucket that stores the database
-//!   files.
-//! - `DATABASE_HEADER_KEY`: object key of the database header file in the S3
-//!   bucket for the database.
+//! - `DATABASE_STORE_URL`: URL of the object store that stores the database
+//!   files, e.g. `s3://bucket/prefix` or `file:///path/to/database`. Only
+//!   the `s3://` and `file://` schemes are accepted; `gs://` and `az://`
+//!   URLs fail because `object_store` is built with only its `aws` feature.
+//! - `DATABASE_HEADER_KEY`: object key of the database header file, relative
+//!   to `DATABASE_STORE_URL`.
 //!
-//! The function accepts an array of 1,536 `f32` values compatible with
-//! [OpenAI's embedding model "text-embedding-ada-002"](https://platform.openai.com/docs/models/embeddings)
-//! and returns an array of [`SimilarMumbling`]s.
+//! When `DATABASE_STORE_URL` uses the `s3://` scheme, `DATABASE_S3_ENDPOINT`,
+//! `DATABASE_S3_REGION`, `DATABASE_S3_FORCE_PATH_STYLE`,
+//! `DATABASE_S3_ACCESS_KEY_ID`, and `DATABASE_S3_SECRET_ACCESS_KEY` may also
+//! be set to reach S3-compatible gateways such as MinIO, Garage, or Ceph
+//! RGW; see [`indexer::store::parse_database_store_url`].
+//! - `DATABASE_VERIFY_CHECKSUMS` (optional): set to `true` to recompute and
+//!   check each database file's `sha256` checksum against the
+//!   `x-amz-meta-sha256` object metadata the indexer wrote, guarding
+//!   against truncated or corrupted reads. Off by default. Enabling it
+//!   makes partition-file reads fetch the whole file instead of just the
+//!   requested byte range, so it costs extra object-store reads.
+//!
+//! The function accepts a [`SearchQuery`] carrying a 1,536-`f32` embedding
+//! compatible with
+//! [OpenAI's embedding model "text-embedding-ada-002"](https://platform.openai.com/docs/models/embeddings),
+//! optional `k`/`nprobe` overrides, extra attributes to hydrate, and
+//! post-query filter predicates, and returns an array of
+//! [`SimilarMumbling`]s.
+//!
+//! Two request encodings are accepted, distinguished by the `Content-Type`
+//! header: a plain JSON body deserializing to [`SearchQuery`], or a
+//! `multipart/form-data` body as parsed by
+//! [`indexer::multipart::parse_multipart_search_query`], so the search can
+//! be driven directly from an HTML form upload.
 
-use anyhow::{anyhow, bail};
+use anyhow::{Context as _, anyhow};
 use flechasdb::db::AttributeValue;
 use flechasdb::asyncdb::stored::{Database, LoadDatabase};
-use flechasdb_s3::asyncfs::S3FileSystem;
 use futures::future::try_join_all;
-use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use lambda_http::{Body, Error, Request, Response, run, service_fn};
 use serde::Serialize;
+use std::collections::HashMap;
 use std::env;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tokio::sync::{OnceCell, RwLock};
 
+use indexer::multipart::{UploadPolicy, parse_multipart_search_query};
+use indexer::query::SearchQuery;
+use indexer::store::{ConditionalRead, ObjectStoreFileSystem, parse_database_store_url};
 use indexer::utils::split_database_header_key;
 
+/// Database loaded on a previous, warm invocation, kept around so that the
+/// common case — the indexer hasn't republished since — skips re-fetching
+/// the header and partition files from the object store.
+struct CachedDatabase {
+    /// ETag of `DATABASE_HEADER_KEY` as of the last load, if it has one.
+    etag: Option<String>,
+    db: Arc<Database<f32, ObjectStoreFileSystem>>,
+}
+
+/// Process-global database cache, reused across warm Lambda invocations.
+static DATABASE_CACHE: OnceCell<RwLock<Option<CachedDatabase>>> = OnceCell::const_new();
+
+async fn database_cache() -> &'static RwLock<Option<CachedDatabase>> {
+    DATABASE_CACHE.get_or_init(|| async { RwLock::new(None) }).await
+}
+
+/// Loads the database, reusing the cached one if `DATABASE_HEADER_KEY`
+/// hasn't changed since the last invocation.
+async fn load_or_reuse_database(
+    fs: &ObjectStoreFileSystem,
+    header_path: &str,
+) -> Result<Arc<Database<f32, ObjectStoreFileSystem>>, Error> {
+    let known_etag = {
+        let cache = database_cache().await.read().await;
+        cache.as_ref().and_then(|cached| cached.etag.clone())
+    };
+    match fs.read_with_etag(header_path, known_etag.as_deref()).await? {
+        ConditionalRead::NotModified => {
+            let cache = database_cache().await.read().await;
+            Ok(cache
+                .as_ref()
+                .expect("cache must be populated to receive a Not Modified response")
+                .db
+                .clone())
+        }
+        ConditionalRead::Modified { etag, .. } => {
+            let db: Database<f32, _> =
+                Database::load_database(fs.clone(), header_path).await?;
+            let db = Arc::new(db);
+            let mut cache = database_cache().await.write().await;
+            *cache = Some(CachedDatabase { etag, db: db.clone() });
+            Ok(db)
+        }
+    }
+}
+
 /// Link to a mumbling in search results.
 #[derive(Serialize)]
 pub struct SimilarMumbling {
@@ -28,40 +103,92 @@ pub struct SimilarMumbling {
     id: String,
     /// Approximate squared distance.
     distance: f32,
+    /// Values of the attributes the query asked to hydrate, keyed by
+    /// attribute name.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    attributes: HashMap<String, AttributeValue>,
 }
 
-async fn function_handler(
-    event: LambdaEvent<Vec<f32>>,
-) -> Result<Vec<SimilarMumbling>, Error> {
-    const K: usize = 30;
-    const NPROBE: usize = 1;
-    let database_bucket_name = env::var("DATABASE_BUCKET_NAME")?;
+async fn run_query(query: SearchQuery) -> Result<Vec<SimilarMumbling>, anyhow::Error> {
+    let database_store_url = env::var("DATABASE_STORE_URL")?;
     let database_header_key = env::var("DATABASE_HEADER_KEY")?;
-    let config = aws_config::load_from_env().await;
+    let store_url = parse_database_store_url(&database_store_url)?;
     let (base_path, header_path) =
         split_database_header_key(&database_header_key)?;
-    let db: Database<f32, _> = Database::load_database(
-        S3FileSystem::new(&config, database_bucket_name, base_path),
-        header_path,
-    ).await?;
-    let results = db.query(
-        &event.payload,
-        K.try_into().unwrap(),
-        NPROBE.try_into().unwrap(),
-    ).await?;
-    let results = try_join_all(results.into_iter().map(|r| async move {
-        r.get_attribute("content_id").await?
-            .ok_or(anyhow!("content_id is not assigned"))
-            .and_then(|value| if let AttributeValue::String(id) = value {
-                Ok(SimilarMumbling {
-                    id,
-                    distance: r.squared_distance,
-                })
-            } else {
-                bail!("content_id is not a string")
-            })
+    let verify_checksums = env::var("DATABASE_VERIFY_CHECKSUMS")
+        .map(|value| value == "true")
+        .unwrap_or(false);
+    let fs = ObjectStoreFileSystem::new(
+        store_url.store,
+        format!("{}/{}", store_url.base_path, base_path),
+    ).with_checksum_verification(verify_checksums);
+    let db = load_or_reuse_database(&fs, header_path).await?;
+    let k = NonZeroUsize::new(query.k).ok_or_else(|| anyhow!("k must be greater than zero"))?;
+    let nprobe = NonZeroUsize::new(query.nprobe)
+        .ok_or_else(|| anyhow!("nprobe must be greater than zero"))?;
+    let results = db.query(&query.embedding, k, nprobe).await?;
+    let results = try_join_all(results.into_iter().map(|r| {
+        let query = &query;
+        async move {
+            let content_id = r.get_attribute("content_id").await?
+                .ok_or(anyhow!("content_id is not assigned"))
+                .and_then(|value| if let AttributeValue::String(id) = value {
+                    Ok(id)
+                } else {
+                    Err(anyhow!("content_id is not a string"))
+                })?;
+            for predicate in &query.filter {
+                let value = r.get_attribute(predicate.attribute()).await?;
+                match value {
+                    Some(value) if predicate.matches(&value) => {}
+                    _ => return Ok(None),
+                }
+            }
+            let mut attributes = HashMap::with_capacity(query.attributes.len());
+            for name in &query.attributes {
+                if let Some(value) = r.get_attribute(name).await? {
+                    attributes.insert(name.clone(), value);
+                }
+            }
+            Ok(Some(SimilarMumbling {
+                id: content_id,
+                distance: r.squared_distance,
+                attributes,
+            }))
+        }
     })).await?;
-    Ok(results)
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Multipart form fields this function accepts, and the limits they're
+/// checked against.
+fn upload_policy() -> UploadPolicy {
+    UploadPolicy::default()
+}
+
+/// Parses `request`'s body into a [`SearchQuery`], accepting either a JSON
+/// body or a `multipart/form-data` body, based on its `Content-Type`.
+async fn parse_search_query(request: &Request) -> Result<SearchQuery, anyhow::Error> {
+    let content_type = request
+        .headers()
+        .get(lambda_http::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if let Ok(boundary) = multer::parse_boundary(content_type) {
+        let body = bytes::Bytes::copy_from_slice(request.body().as_ref());
+        return parse_multipart_search_query(body, &boundary, &upload_policy()).await;
+    }
+    serde_json::from_slice(request.body().as_ref())
+        .context("request body is not a valid SearchQuery")
+}
+
+async fn function_handler(request: Request) -> Result<Response<Body>, Error> {
+    let query = parse_search_query(&request).await?;
+    let results = run_query(query).await?;
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(Body::Text(serde_json::to_string(&results)?))?)
 }
 
 #[tokio::main]
@@ -0,0 +1,6 @@
+//! Library crate backing the `indexer` Lambda binaries.
+
+pub mod multipart;
+pub mod query;
+pub mod store;
+pub mod utils;
@@ -0,0 +1,240 @@
+//! `multipart/form-data` entry path for similarity-search queries.
+//!
+//! Lets a query be driven directly from an HTML form or a browser upload
+//! instead of a JSON body: a file field carries the embedding and ordinary
+//! form fields carry `k`, `nprobe`, `attributes`, and `filter`. Every field
+//! name and the requested `k` are checked against an [`UploadPolicy`] before
+//! the form is turned into a [`SearchQuery`].
+
+use crate::query::SearchQuery;
+use anyhow::{Context as _, bail};
+use bytes::Bytes;
+use futures::stream;
+use multer::Multipart;
+
+/// Name of the multipart field carrying the embedding.
+const EMBEDDING_FIELD: &str = "embedding";
+
+/// Number of `f32`s in an
+/// [OpenAI "text-embedding-ada-002"](https://platform.openai.com/docs/models/embeddings)
+/// embedding.
+const EMBEDDING_LEN: usize = 1536;
+
+/// Server-side policy a multipart query is validated against.
+pub struct UploadPolicy {
+    /// Form field names allowed besides [`EMBEDDING_FIELD`], which is
+    /// always allowed.
+    pub allowed_fields: Vec<&'static str>,
+    /// Largest `k` a query may ask for.
+    pub max_k: usize,
+    /// Largest accepted body size, in bytes.
+    pub max_content_length: usize,
+}
+
+impl Default for UploadPolicy {
+    /// Allows the fields [`SearchQuery`] understands, caps `k` at 1,000,
+    /// and caps the body at 16 MiB.
+    fn default() -> Self {
+        Self {
+            allowed_fields: vec!["k", "nprobe", "attributes", "filter"],
+            max_k: 1_000,
+            max_content_length: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl UploadPolicy {
+    fn allows(&self, field: &str) -> bool {
+        field == EMBEDDING_FIELD || self.allowed_fields.iter().any(|allowed| *allowed == field)
+    }
+}
+
+/// Parses a `multipart/form-data` body into a [`SearchQuery`], validating
+/// every field against `policy`.
+///
+/// `boundary` is the `boundary` parameter of the request's `Content-Type`
+/// header. `body` is expected to already be buffered in memory by the
+/// caller (e.g. `lambda_http` buffers the whole request before handing it
+/// off), so `max_content_length` rejects an oversized body here rather than
+/// bounding how much of it gets buffered in the first place.
+pub async fn parse_multipart_search_query(
+    body: Bytes,
+    boundary: &str,
+    policy: &UploadPolicy,
+) -> Result<SearchQuery, anyhow::Error> {
+    if body.len() > policy.max_content_length {
+        bail!(
+            "request body of {} bytes exceeds the {}-byte limit",
+            body.len(),
+            policy.max_content_length,
+        );
+    }
+    let mut multipart = Multipart::new(
+        stream::once(async move { Ok::<_, std::io::Error>(body) }),
+        boundary,
+    );
+
+    let mut embedding = None;
+    let mut k = None;
+    let mut nprobe = None;
+    let mut attributes = Vec::new();
+    let mut filter = Vec::new();
+
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        if !policy.allows(&name) {
+            bail!("field \"{name}\" is not allowed by the upload policy");
+        }
+        match name.as_str() {
+            EMBEDDING_FIELD => embedding = Some(parse_embedding(&field.bytes().await?)?),
+            "k" => {
+                k = Some(
+                    field
+                        .text()
+                        .await?
+                        .parse()
+                        .context("k must be an unsigned integer")?,
+                )
+            }
+            "nprobe" => {
+                nprobe = Some(
+                    field
+                        .text()
+                        .await?
+                        .parse()
+                        .context("nprobe must be an unsigned integer")?,
+                )
+            }
+            "attributes" => {
+                attributes = serde_json::from_str(&field.text().await?)
+                    .context("attributes must be a JSON array of attribute names")?;
+            }
+            "filter" => {
+                filter = serde_json::from_str(&field.text().await?)
+                    .context("filter must be a JSON array of predicates")?;
+            }
+            _ => {}
+        }
+    }
+
+    let embedding = embedding.context("missing the embedding field")?;
+    let k = k.unwrap_or(30);
+    if k > policy.max_k {
+        bail!("k={k} exceeds the policy maximum of {}", policy.max_k);
+    }
+    Ok(SearchQuery {
+        embedding,
+        k,
+        nprobe: nprobe.unwrap_or(1),
+        attributes,
+        filter,
+    })
+}
+
+/// Parses the embedding field's bytes, accepting either
+/// [`EMBEDDING_LEN`] little-endian `f32`s or a JSON array of floats.
+fn parse_embedding(bytes: &[u8]) -> Result<Vec<f32>, anyhow::Error> {
+    if bytes.len() == EMBEDDING_LEN * 4 {
+        return Ok(bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect());
+    }
+    serde_json::from_slice(bytes)
+        .context("embedding must be raw little-endian f32 bytes or a JSON array of floats")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn multipart_body(boundary: &str, parts: &[(&str, &[u8])]) -> Bytes {
+        let mut body = Vec::new();
+        for (name, value) in parts {
+            body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+            body.extend_from_slice(
+                format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+            );
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+        Bytes::from(body)
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_search_query_should_parse_a_json_embedding_with_defaults() {
+        let boundary = "boundary";
+        let body = multipart_body(boundary, &[("embedding", b"[1.0,2.0,3.0]")]);
+        let query = parse_multipart_search_query(body, boundary, &UploadPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(query.embedding, vec![1.0, 2.0, 3.0]);
+        assert_eq!(query.k, 30);
+        assert_eq!(query.nprobe, 1);
+        assert!(query.attributes.is_empty());
+        assert!(query.filter.is_empty());
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_search_query_should_parse_raw_little_endian_f32_bytes() {
+        let boundary = "boundary";
+        let mut embedding = vec![0u8; EMBEDDING_LEN * 4];
+        embedding[0..4].copy_from_slice(&1.5f32.to_le_bytes());
+        let body = multipart_body(boundary, &[("embedding", &embedding)]);
+        let query = parse_multipart_search_query(body, boundary, &UploadPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(query.embedding.len(), EMBEDDING_LEN);
+        assert_eq!(query.embedding[0], 1.5);
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_search_query_should_honor_k_and_nprobe_fields() {
+        let boundary = "boundary";
+        let body = multipart_body(
+            boundary,
+            &[("embedding", b"[1.0]"), ("k", b"5"), ("nprobe", b"2")],
+        );
+        let query = parse_multipart_search_query(body, boundary, &UploadPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(query.k, 5);
+        assert_eq!(query.nprobe, 2);
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_search_query_should_fail_if_k_exceeds_the_policy_maximum() {
+        let boundary = "boundary";
+        let body = multipart_body(boundary, &[("embedding", b"[1.0]"), ("k", b"10")]);
+        let policy = UploadPolicy {
+            max_k: 5,
+            ..UploadPolicy::default()
+        };
+        assert!(parse_multipart_search_query(body, boundary, &policy)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_search_query_should_fail_on_a_field_not_allowed_by_the_policy() {
+        let boundary = "boundary";
+        let body = multipart_body(boundary, &[("embedding", b"[1.0]"), ("admin", b"true")]);
+        assert!(
+            parse_multipart_search_query(body, boundary, &UploadPolicy::default())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_search_query_should_fail_without_an_embedding_field() {
+        let boundary = "boundary";
+        let body = multipart_body(boundary, &[("k", b"5")]);
+        assert!(
+            parse_multipart_search_query(body, boundary, &UploadPolicy::default())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Structured similarity-search queries.
+//!
+//! Replaces the bare `Vec<f32>` embedding payload with a request that also
+//! carries `k`/`nprobe` overrides, the extra attributes to hydrate on each
+//! result, and a set of predicates results are post-filtered against.
+
+use flechasdb::db::AttributeValue;
+use serde::Deserialize;
+use std::cmp::Ordering;
+
+fn default_k() -> usize {
+    30
+}
+
+fn default_nprobe() -> usize {
+    1
+}
+
+/// A similarity-search request.
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// Embedding vector to search for similar mumblings, compatible with
+    /// [OpenAI's embedding model "text-embedding-ada-002"](https://platform.openai.com/docs/models/embeddings).
+    pub embedding: Vec<f32>,
+    /// Maximum number of results to return.
+    #[serde(default = "default_k")]
+    pub k: usize,
+    /// Number of partitions (IVF cells) to probe.
+    #[serde(default = "default_nprobe")]
+    pub nprobe: usize,
+    /// Extra attribute names to hydrate and return alongside `distance` on
+    /// each result.
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    /// Predicates every returned result must satisfy.
+    #[serde(default)]
+    pub filter: Vec<Predicate>,
+}
+
+/// A predicate a search result's attributes must satisfy.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Predicate {
+    /// The named attribute's value must equal `value`.
+    Eq {
+        attribute: String,
+        value: AttributeValue,
+    },
+    /// The named attribute's value must fall within `min..=max`. Either
+    /// bound may be omitted to leave that side unconstrained.
+    Range {
+        attribute: String,
+        #[serde(default)]
+        min: Option<AttributeValue>,
+        #[serde(default)]
+        max: Option<AttributeValue>,
+    },
+}
+
+impl Predicate {
+    /// Name of the attribute this predicate constrains.
+    pub fn attribute(&self) -> &str {
+        match self {
+            Self::Eq { attribute, .. } => attribute,
+            Self::Range { attribute, .. } => attribute,
+        }
+    }
+
+    /// Whether `value`, the named attribute's value on a given result,
+    /// satisfies this predicate.
+    pub fn matches(&self, value: &AttributeValue) -> bool {
+        match self {
+            Self::Eq { value: expected, .. } => value == expected,
+            Self::Range { min, max, .. } => {
+                min.as_ref().map_or(true, |min| {
+                    matches!(compare(value, min), Some(Ordering::Equal | Ordering::Greater))
+                }) && max.as_ref().map_or(true, |max| {
+                    matches!(compare(value, max), Some(Ordering::Equal | Ordering::Less))
+                })
+            }
+        }
+    }
+}
+
+/// Compares two [`AttributeValue`]s of the same variant; unrelated variants
+/// are considered incomparable, so a `Range` predicate against them never
+/// matches.
+fn compare(a: &AttributeValue, b: &AttributeValue) -> Option<Ordering> {
+    match (a, b) {
+        (AttributeValue::String(a), AttributeValue::String(b)) => a.partial_cmp(b),
+        (AttributeValue::Int64(a), AttributeValue::Int64(b)) => a.partial_cmp(b),
+        (AttributeValue::Float64(a), AttributeValue::Float64(b)) => a.partial_cmp(b),
+        _ => None,
+    }
+}
@@ -0,0 +1,286 @@
+//! Storage-agnostic backing for the vector database.
+//!
+//! [`ObjectStoreFileSystem`] implements [`flechasdb`]'s `asyncfs` trait on
+//! top of [`object_store`], the generic object-storage interface that
+//! unifies AWS S3, Google Cloud Storage, Azure Blob Storage, and the local
+//! filesystem behind one `get`/`list`/`head` API. This lets
+//! `search-similar` load the same vector database from a local disk during
+//! tests and from whichever cloud storage backs production, without
+//! depending on `flechasdb_s3` or AWS-specific configuration.
+
+use std::env;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context as _;
+use flechasdb::asyncfs::AsyncFileSystem;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path as StorePath;
+use object_store::{Attribute, Attributes, Error as StoreError, GetOptions, GetResult, ObjectStore};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Object metadata key the database files' checksums are stored under,
+/// following the `x-amz-meta-*` convention S3 exposes custom metadata
+/// through (i.e. the indexer writes `x-amz-meta-sha256`, and `object_store`
+/// surfaces it back as `Attribute::Metadata("sha256")`).
+const CHECKSUM_METADATA_KEY: &str = "sha256";
+
+/// File system backed by an [`object_store::ObjectStore`], rooted at a base
+/// path within that store.
+///
+/// Every path handed to [`AsyncFileSystem`] methods is resolved relative to
+/// `base_path`, mirroring how `flechasdb_s3::asyncfs::S3FileSystem`
+/// resolves paths relative to a bucket and prefix.
+#[derive(Clone)]
+pub struct ObjectStoreFileSystem {
+    store: Arc<dyn ObjectStore>,
+    base_path: StorePath,
+    verify_checksums: bool,
+    /// Holds the body [`read_with_etag`](Self::read_with_etag) already
+    /// fetched for a path, so that the next [`read`](AsyncFileSystem::read)
+    /// of that same path (typically the header load
+    /// [`read_with_etag`](Self::read_with_etag) was priming for) is served
+    /// from memory instead of re-fetching the object.
+    primed: Arc<Mutex<Option<(StorePath, Vec<u8>)>>>,
+}
+
+impl ObjectStoreFileSystem {
+    /// Wraps `store`, rooting every subsequent path at `base_path`.
+    ///
+    /// Checksum verification is off by default; enable it with
+    /// [`Self::with_checksum_verification`].
+    pub fn new(store: Arc<dyn ObjectStore>, base_path: impl AsRef<str>) -> Self {
+        Self {
+            store,
+            base_path: StorePath::from(base_path.as_ref()),
+            verify_checksums: false,
+            primed: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Enables or disables recomputing and checking each file's `sha256`
+    /// checksum against the `x-amz-meta-sha256` object metadata the indexer
+    /// wrote when it uploaded the file.
+    pub fn with_checksum_verification(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    /// Resolves `path` relative to the file system's base path.
+    fn resolve(&self, path: &str) -> StorePath {
+        self.base_path.parts().chain(StorePath::from(path).parts()).collect()
+    }
+
+    /// Verifies `bytes` against the `sha256` checksum stored in `path`'s
+    /// object metadata, if checksum verification is enabled and a checksum
+    /// was stored.
+    fn verify_checksum(
+        &self,
+        path: &str,
+        attributes: &Attributes,
+        bytes: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        if !self.verify_checksums {
+            return Ok(());
+        }
+        let Some(expected) = attributes.get(&Attribute::Metadata(CHECKSUM_METADATA_KEY.into()))
+        else {
+            return Ok(());
+        };
+        let actual = format!("{:x}", Sha256::digest(bytes));
+        if actual != *expected {
+            anyhow::bail!(
+                "checksum mismatch for {path}: expected {expected}, computed {actual}"
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads `path`, skipping the body if its ETag still matches
+    /// `known_etag`.
+    ///
+    /// Issues a conditional `GET` with `If-None-Match: <known_etag>` (the
+    /// same precondition S3 and friends define for `If-Match`/
+    /// `If-None-Match`). Returns [`ConditionalRead::NotModified`] when the
+    /// store replies that the object hasn't changed, so callers can reuse
+    /// whatever they cached the last time they saw `known_etag`. If the
+    /// object has no ETag (some backends don't surface one), the object is
+    /// treated as not cacheable and always read in full.
+    ///
+    /// On a [`ConditionalRead::Modified`], the fetched body is primed into
+    /// this file system so that the very next [`AsyncFileSystem::read`] of
+    /// `path` (e.g. [`Database::load_database`](flechasdb::asyncdb::stored::Database::load_database)
+    /// re-reading the header it was just conditionally fetched for) is
+    /// served from memory rather than fetching `path` from the store again.
+    pub async fn read_with_etag(
+        &self,
+        path: &str,
+        known_etag: Option<&str>,
+    ) -> Result<ConditionalRead, anyhow::Error> {
+        let options = GetOptions {
+            if_none_match: known_etag.map(String::from),
+            ..Default::default()
+        };
+        let resolved = self.resolve(path);
+        match self.store.get_opts(&resolved, options).await {
+            Ok(result) => {
+                let etag = result.meta.e_tag.clone();
+                let attributes = result.attributes.clone();
+                let bytes = result.bytes().await?.to_vec();
+                self.verify_checksum(path, &attributes, &bytes)?;
+                *self.primed.lock().unwrap() = Some((resolved, bytes.clone()));
+                Ok(ConditionalRead::Modified { etag, bytes })
+            }
+            Err(StoreError::NotModified { .. }) => Ok(ConditionalRead::NotModified),
+            Err(err) => Err(err).with_context(|| format!("failed to read {path}")),
+        }
+    }
+}
+
+/// Outcome of [`ObjectStoreFileSystem::read_with_etag`].
+pub enum ConditionalRead {
+    /// The object's ETag still matches what the caller already has cached.
+    NotModified,
+    /// The object changed (or the caller had nothing cached yet); its
+    /// contents are returned along with its ETag, if it has one. `None`
+    /// means the object isn't cacheable, so the caller shouldn't bother
+    /// sending `known_etag` for it on the next call.
+    Modified { etag: Option<String>, bytes: Vec<u8> },
+}
+
+impl AsyncFileSystem for ObjectStoreFileSystem {
+    type Error = anyhow::Error;
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        let resolved = self.resolve(path);
+        {
+            let mut primed = self.primed.lock().unwrap();
+            if primed.as_ref().is_some_and(|(primed_path, _)| *primed_path == resolved) {
+                return Ok(primed.take().unwrap().1);
+            }
+        }
+        let result: GetResult = self
+            .store
+            .get(&resolved)
+            .await
+            .with_context(|| format!("failed to read {path}"))?;
+        let attributes = result.attributes.clone();
+        let bytes = result.bytes().await?.to_vec();
+        self.verify_checksum(path, &attributes, &bytes)?;
+        Ok(bytes)
+    }
+
+    async fn read_range(
+        &self,
+        path: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Self::Error> {
+        let range = (range.start as usize)..(range.end as usize);
+        if self.verify_checksums {
+            // A checksum covers the whole file, not an arbitrary byte
+            // range, so verifying it here means fetching the whole
+            // partition file rather than just the requested range. That's
+            // the safety cost `DATABASE_VERIFY_CHECKSUMS` trades off
+            // against the cheaper, unverified `get_range` below.
+            let result = self
+                .store
+                .get(&self.resolve(path))
+                .await
+                .with_context(|| format!("failed to read {path}"))?;
+            let attributes = result.attributes.clone();
+            let bytes = result.bytes().await?;
+            self.verify_checksum(path, &attributes, &bytes)?;
+            if range.end > bytes.len() {
+                anyhow::bail!(
+                    "requested range {range:?} exceeds the length of {path} ({} bytes)",
+                    bytes.len(),
+                );
+            }
+            return Ok(bytes.slice(range).to_vec());
+        }
+        let bytes = self
+            .store
+            .get_range(&self.resolve(path), range)
+            .await
+            .with_context(|| format!("failed to read a range of {path}"))?;
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Database store location parsed out of `DATABASE_STORE_URL`.
+///
+/// Supports the `s3://` and `file://` schemes. `object_store` is built with
+/// only its `aws` feature, so [`object_store::parse_url`] rejects `gs://`
+/// and `az://` URLs with an error. Unlike `DATABASE_BUCKET_NAME`, which only
+/// ever meant an S3 bucket, `DATABASE_STORE_URL` carries both the backend
+/// and the bucket/directory the database files live under, e.g.
+/// `s3://bucket/prefix` or `file:///var/db`.
+pub struct DatabaseStoreUrl {
+    pub store: Arc<dyn ObjectStore>,
+    pub base_path: StorePath,
+}
+
+/// Parses `DATABASE_STORE_URL` into a [`DatabaseStoreUrl`].
+///
+/// `s3://` URLs are built through [`AmazonS3Builder`] instead of
+/// [`object_store::parse_url`] so that self-hosted S3-compatible gateways
+/// (MinIO, Garage, Ceph RGW, ...) can be reached. The following environment
+/// variables configure that, all optional:
+/// - `DATABASE_S3_ENDPOINT`: explicit endpoint URL, for gateways that aren't
+///   `*.amazonaws.com`.
+/// - `DATABASE_S3_REGION`: region string to send, for gateways that require
+///   one but aren't in an AWS region.
+/// - `DATABASE_S3_FORCE_PATH_STYLE`: `true` to address objects as
+///   `endpoint/bucket/key` instead of `bucket.endpoint/key`, as most
+///   self-hosted gateways require.
+/// - `DATABASE_S3_ACCESS_KEY_ID` / `DATABASE_S3_SECRET_ACCESS_KEY`: static
+///   credentials, overriding whatever [`AmazonS3Builder::from_env`] would
+///   otherwise pick up.
+pub fn parse_database_store_url(url: &str) -> Result<DatabaseStoreUrl, anyhow::Error> {
+    let url = Url::parse(url)
+        .with_context(|| format!("invalid DATABASE_STORE_URL: {url}"))?;
+    if url.scheme() == "s3" {
+        return parse_s3_database_store_url(&url);
+    }
+    let (store, base_path) = object_store::parse_url(&url)
+        .with_context(|| format!("failed to build an object store for {url}"))?;
+    Ok(DatabaseStoreUrl {
+        store: Arc::from(store),
+        base_path,
+    })
+}
+
+/// Builds a [`DatabaseStoreUrl`] for an `s3://bucket/prefix` URL, threading
+/// the `DATABASE_S3_*` environment variables into the SDK config.
+fn parse_s3_database_store_url(url: &Url) -> Result<DatabaseStoreUrl, anyhow::Error> {
+    let bucket_name = url
+        .host_str()
+        .with_context(|| format!("{url} has no bucket name"))?;
+    let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket_name);
+    if let Ok(endpoint) = env::var("DATABASE_S3_ENDPOINT") {
+        builder = builder.with_endpoint(endpoint);
+    }
+    if let Ok(region) = env::var("DATABASE_S3_REGION") {
+        builder = builder.with_region(region);
+    }
+    if let Ok(force_path_style) = env::var("DATABASE_S3_FORCE_PATH_STYLE") {
+        let force_path_style = force_path_style
+            .parse::<bool>()
+            .context("DATABASE_S3_FORCE_PATH_STYLE must be \"true\" or \"false\"")?;
+        builder = builder.with_virtual_hosted_style_request(!force_path_style);
+    }
+    if let Ok(access_key_id) = env::var("DATABASE_S3_ACCESS_KEY_ID") {
+        builder = builder.with_access_key_id(access_key_id);
+    }
+    if let Ok(secret_access_key) = env::var("DATABASE_S3_SECRET_ACCESS_KEY") {
+        builder = builder.with_secret_access_key(secret_access_key);
+    }
+    let store = builder
+        .build()
+        .with_context(|| format!("failed to build an S3 object store for {url}"))?;
+    Ok(DatabaseStoreUrl {
+        store: Arc::new(store),
+        base_path: StorePath::from(url.path()),
+    })
+}